@@ -6,14 +6,24 @@ use crate::verb_invocation::VerbInvocation;
 use crate::event::Event;
 use crossterm::KeyEvent;
 use regex::Regex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 pub struct Command {
     pub raw: String,     // what's visible in the input
     parts: CommandParts, // the parsed parts of the visible input
     pub action: Action, // what's required, based on the last key (which may be not visible, like esc)
+    pub finished: bool,  // whether this command was submitted (like hitting enter) rather than left being edited
+    keymap: Keymap,      // the keybindings used to turn keys into actions
+    pending: Vec<KeyEvent>, // keys of a chord typed so far, not yet resolved to an action
+    pending_since: Option<Instant>, // when `pending` started, to resolve it on timeout
 }
 
+/// the character separating successive commands in a batch script, as
+///  passed to `--cmd` or read from a script file
+pub const SCRIPT_SEPARATOR: char = ';';
+
 /// An intermediate parsed representation of the raw string
 #[derive(Debug, Clone)]
 struct CommandParts {
@@ -39,7 +49,177 @@ pub enum Action {
     Quit,                      // quit broot
     Click(u16, u16),           // usually a mouse click
     DoubleClick(u16, u16),     // always come after a simple click at same position
-    Unparsed,                  // or unparsable
+    // a chain of actions, executed in order by the app loop; a key or a verb
+    // invocation may resolve to one of these instead of to a single action,
+    // letting a config author compose existing primitives. Execution should
+    // stop as soon as one of the actions changes app state (e.g. Back, Quit).
+    // `Command::add_key` produces one itself when an ambiguous chord's
+    // shorter binding fires alongside the key that failed to extend it; a
+    // `Keymap` binding can also be given one directly, via `Keymap::insert`
+    // or `Keymap::insert_chord`, to chain existing actions under one key.
+    Sequence(Vec<Action>),
+    Unparsed, // or unparsable
+}
+
+/// A user-configurable mapping from key chords to actions, consulted by
+///  `Command::add_key` before it falls back to the char-insertion /
+///  pattern-editing default behavior.
+/// Bindings are stored as a trie keyed by `KeyEvent`: any node, inner
+///  ones included, may carry an `Action`, which is what lets a single key (e.g. `Ctrl-x`)
+///  be both a complete binding and the prefix of a longer chord (e.g.
+///  `Ctrl-x Ctrl-s`).
+/// Loaded from broot's config file; `Keymap::default()` reproduces the
+///  bindings broot used to hardcode so nothing changes when there's no
+///  config for it.
+/// A key which is both a complete binding and the prefix of a longer
+///  chord (e.g. `g` bound to `Refresh`, `g g` bound to something else)
+///  is kept pending until either another key extends it, or
+///  `chord_timeout` elapses with no further key, at which point the
+///  shorter binding fires (see `Command::check_chord_timeout`).
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    root: KeymapNode,
+    chord_timeout: Duration,
+}
+
+/// how long a pending, ambiguous chord waits for a continuation before
+///  its shorter binding fires
+const DEFAULT_CHORD_TIMEOUT: Duration = Duration::from_millis(600);
+
+#[derive(Debug, Clone, Default)]
+struct KeymapNode {
+    action: Option<Action>,
+    children: HashMap<KeyEvent, KeymapNode>,
+}
+
+/// result of looking up a (possibly partial) chord in the keymap
+enum ChordMatch {
+    Action(Action), // the chord is complete and bound to this action
+    Pending,        // the chord is an incomplete prefix of at least one binding
+    None,           // the chord isn't and can't become a binding
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap {
+            root: KeymapNode::default(),
+            chord_timeout: DEFAULT_CHORD_TIMEOUT,
+        }
+    }
+
+    /// bind a single key, equivalent to a chord of length one
+    pub fn insert(&mut self, key: KeyEvent, action: Action) {
+        self.insert_chord(&[key], action);
+    }
+
+    /// bind a sequence of keys (e.g. `Ctrl-x Ctrl-s`) to an action
+    pub fn insert_chord(&mut self, keys: &[KeyEvent], action: Action) {
+        let mut node = &mut self.root;
+        for key in keys {
+            node = node.children.entry(key.clone()).or_default();
+        }
+        node.action = Some(action);
+    }
+
+    /// set how long a pending, ambiguous chord waits for a continuation
+    ///  before its shorter binding fires
+    pub fn set_chord_timeout(&mut self, timeout: Duration) {
+        self.chord_timeout = timeout;
+    }
+
+    /// look up a chord typed so far, walking the trie from the root
+    fn lookup(&self, keys: &[KeyEvent]) -> ChordMatch {
+        let mut node = &self.root;
+        for key in keys {
+            match node.children.get(key) {
+                Some(child) => node = child,
+                None => return ChordMatch::None,
+            }
+        }
+        match &node.action {
+            // a key that's both bound and a prefix of a longer chord is kept
+            // pending: it only fires once no further key extends it
+            Some(_) if !node.children.is_empty() => ChordMatch::Pending,
+            Some(action) => ChordMatch::Action(action.clone()),
+            None if node.children.is_empty() => ChordMatch::None,
+            None => ChordMatch::Pending,
+        }
+    }
+
+    /// the action bound to this exact key sequence, if any, regardless of
+    ///  whether it's also the prefix of a longer chord. Used to resolve a
+    ///  pending chord once it's given up on being extended.
+    fn action_at(&self, keys: &[KeyEvent]) -> Option<Action> {
+        let mut node = &self.root;
+        for key in keys {
+            node = node.children.get(key)?;
+        }
+        node.action.clone()
+    }
+}
+
+impl Default for Keymap {
+    /// the bindings broot has always had, kept as the default so that
+    ///  a user with no config section for keybindings sees no change
+    fn default() -> Keymap {
+        let mut km = Keymap::new();
+        km.insert(KeyEvent::Alt('\r'), Action::AltOpenSelection);
+        km.insert(KeyEvent::Alt('\n'), Action::AltOpenSelection);
+        km.insert(KeyEvent::Ctrl('q'), Action::Quit);
+        km.insert(KeyEvent::Up, Action::MoveSelection(-1));
+        km.insert(KeyEvent::Down, Action::MoveSelection(1));
+        km.insert(KeyEvent::F(5), Action::Refresh);
+        km.insert(KeyEvent::PageUp, Action::ScrollPage(-1));
+        km.insert(KeyEvent::Ctrl('u'), Action::ScrollPage(-1));
+        km.insert(KeyEvent::PageDown, Action::ScrollPage(1));
+        km.insert(KeyEvent::Ctrl('d'), Action::ScrollPage(1));
+        km
+    }
+}
+
+/// split `raw` on `separator`, but never inside a single- or double-quoted
+///  run or right after a backslash, mirroring the quoting rules of a verb's
+///  argument tokenizer. Quotes and escapes are kept in the returned parts
+///  (they're still raw command strings, to be parsed by `CommandParts::from`
+///  and, in turn, `VerbInvocation::from`).
+fn split_unquoted(raw: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for c in raw.chars() {
+        if escaped {
+            part.push(c);
+            escaped = false;
+            continue;
+        }
+        match quote {
+            Some(q) => {
+                part.push(c);
+                if c == q {
+                    quote = None;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                }
+            }
+            None if c == '\\' => {
+                part.push(c);
+                escaped = true;
+            }
+            None if c == '\'' || c == '"' => {
+                part.push(c);
+                quote = Some(c);
+            }
+            None if c == separator => {
+                parts.push(std::mem::take(&mut part));
+            }
+            None => {
+                part.push(c);
+            }
+        }
+    }
+    parts.push(part);
+    parts
 }
 
 impl CommandParts {
@@ -111,22 +291,70 @@ impl Command {
             raw: String::new(),
             parts: CommandParts::new(),
             action: Action::Unparsed,
+            finished: false,
+            keymap: Keymap::default(),
+            pending: Vec::new(),
+            pending_since: None,
+        }
+    }
+
+    /// build a command using a specific keymap, for example one loaded
+    ///  from the user's config file
+    pub fn with_keymap(keymap: Keymap) -> Command {
+        Command {
+            keymap,
+            ..Command::new()
         }
     }
 
-    // build a command from a string
+    // build a single command from a string
     // Note that this isn't used (or usable) for interpretation
     //  of the in-app user input. It's meant for interpretation
-    //  of a file or from a sequence of commands passed as argument
-    //  of the program.
+    //  of one part of a script, already split by `parse_script`
+    //  (or of a whole `--cmd` argument made of a single command).
     // A ':', even if at the end, is assumed to mean that the
     //  command must be executed (it's equivalent to the user
-    //  typing `enter` in the app
-    // This specific syntax isn't definitive
+    //  typing `enter` in the app)
     pub fn from(raw: String) -> Command {
         let parts = CommandParts::from(&raw);
-        let action = Action::from(&parts, raw.contains(':'));
-        Command { raw, parts, action }
+        let finished = raw.contains(':');
+        let action = Action::from(&parts, finished);
+        Command {
+            raw,
+            parts,
+            action,
+            finished,
+            keymap: Keymap::default(),
+            pending: Vec::new(),
+            pending_since: None,
+        }
+    }
+
+    /// parse a batch script (the whole of a `--cmd` argument, or the
+    ///  content of a script file) into the ordered sequence of commands
+    ///  it describes, using the default `SCRIPT_SEPARATOR` (`;`) between
+    ///  commands.
+    pub fn parse_script(raw: &str) -> Vec<Command> {
+        Command::parse_script_with_separator(raw, SCRIPT_SEPARATOR)
+    }
+
+    /// like `parse_script`, but with a configurable delimiter between
+    ///  commands, for config authors who want something other than `;`
+    ///  (e.g. because a verb of theirs routinely needs a literal `;`).
+    /// A script is a succession of command strings separated by that
+    ///  delimiter; each of them is parsed by `Command::from`, so a bare or
+    ///  trailing `:` inside one still means "submit this command", letting
+    ///  e.g. `/foo:rm;/bar:cp dest` run a verb on `foo` then another on
+    ///  `bar`. The split is quote- and escape-aware (the same rules as a
+    ///  verb's own argument tokenizer), so a delimiter occurring inside a
+    ///  quoted verb argument - e.g. `/foo:mv "a;b" dest` - doesn't cut the
+    ///  command in half.
+    pub fn parse_script_with_separator(raw: &str, separator: char) -> Vec<Command> {
+        split_unquoted(raw, separator)
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .map(Command::from)
+            .collect()
     }
 
     pub fn add_event(&mut self, event: Event) {
@@ -143,58 +371,215 @@ impl Command {
         }
     }
 
-    fn add_key(&mut self, key: KeyEvent) {
-        match key {
-            KeyEvent::Char('\t') => {
-                self.action = Action::Next;
-            }
-            KeyEvent::Char('\n') => {
-                self.action = Action::from(&self.parts, true);
-            }
-            KeyEvent::Alt('\r') | KeyEvent::Alt('\n') => {
-                self.action = Action::AltOpenSelection;
-            }
-            KeyEvent::Ctrl('q') => {
-                self.action = Action::Quit;
-            }
-            KeyEvent::Up => {
-                self.action = Action::MoveSelection(-1);
-            }
-            KeyEvent::Down => {
-                self.action = Action::MoveSelection(1);
+    /// resolve a pending, ambiguous chord (one whose current prefix is
+    ///  itself bound, but might still be extended) once `chord_timeout`
+    ///  has elapsed with no further key. Meant to be polled regularly by
+    ///  the app loop (e.g. once per UI tick).
+    pub fn check_chord_timeout(&mut self) {
+        if let Some(since) = self.pending_since {
+            if since.elapsed() >= self.keymap.chord_timeout {
+                if let Some(action) = self.keymap.action_at(&self.pending) {
+                    self.action = action;
+                }
+                self.pending.clear();
+                self.pending_since = None;
             }
-            KeyEvent::F(5) => {
-                self.action = Action::Refresh;
+        }
+    }
+
+    fn add_key(&mut self, key: KeyEvent) {
+        if self.pending.is_empty() && key == KeyEvent::Char('?') {
+            // '?' is context-sensitive (see resolve_plain_key): whether it
+            // opens help or is typed as a plain character depends on where
+            // the input currently is, not just on the keymap, so outside a
+            // pending chord it isn't dispatched through the generic chord
+            // trie lookup below - that would fire a remapped binding (or the
+            // default Help) unconditionally, stealing '?' from a pattern.
+            self.action = self.resolve_plain_key(key);
+            return;
+        }
+        if key == KeyEvent::Esc && !self.pending.is_empty() {
+            // Esc always aborts a pending chord and resets to the root, even
+            // where a binding would let it extend the chord, rather than
+            // going through the fallback logic below: it shouldn't be chained
+            // after whatever the abandoned chord's prefix was bound to.
+            self.pending.clear();
+            self.pending_since = None;
+            self.action = Action::Back;
+            return;
+        }
+        let mut chord = self.pending.clone();
+        chord.push(key.clone());
+        match self.keymap.lookup(&chord) {
+            ChordMatch::Action(action) => {
+                self.pending.clear();
+                self.pending_since = None;
+                self.action = action;
             }
-            KeyEvent::PageUp | KeyEvent::Ctrl('u') => {
-                self.action = Action::ScrollPage(-1);
+            ChordMatch::Pending => {
+                // incomplete (or ambiguous) chord: remember it and let the UI
+                // show it's pending. Esc always aborts it (see above);
+                // `check_chord_timeout` resolves it to
+                // its shorter binding, if it has one, once it gives up on
+                // being extended.
+                self.pending = chord;
+                self.pending_since = Some(Instant::now());
+                self.action = Action::Unparsed;
             }
-            KeyEvent::PageDown | KeyEvent::Ctrl('d') => {
-                self.action = Action::ScrollPage(1);
+            ChordMatch::None => {
+                // the new key doesn't extend the pending chord. If that
+                // chord's prefix was itself a complete binding, it must still
+                // fire instead of being silently dropped - chained with
+                // whatever `key` resolves to on its own, since it wasn't
+                // part of the abandoned chord.
+                let fallback = self.keymap.action_at(&self.pending);
+                self.pending.clear();
+                self.pending_since = None;
+                let resolved = self.resolve_plain_key(key);
+                self.action = match fallback {
+                    Some(action) => Action::Sequence(vec![action, resolved]),
+                    None => resolved,
+                };
             }
+        }
+    }
+
+    /// resolve a key outside of any chord context: typing, backspace, esc...
+    fn resolve_plain_key(&mut self, key: KeyEvent) -> Action {
+        let help_key = key.clone();
+        match key {
+            KeyEvent::Char('\t') => Action::Next,
+            KeyEvent::Char('\n') => Action::from(&self.parts, true),
             KeyEvent::Char(c) if c =='?' && (self.raw.is_empty() || self.parts.verb_invocation.is_some()) => {
                 // a '?' opens the help when it's the first char or when it's part of the verb
-                // invocation
-                self.action = Action::Help;
+                // invocation - remappable, like the rest of the keymap, but
+                // still gated on this context so it doesn't steal '?' from
+                // a pattern; once the verb separator is typed, a '?' among
+                // the verb's arguments opens the help too
+                self.keymap.action_at(&[help_key]).unwrap_or(Action::Help)
             }
             KeyEvent::Char(c) => {
                 self.raw.push(c);
                 self.parts = CommandParts::from(&self.raw);
-                self.action = Action::from(&self.parts, false);
-            }
-            KeyEvent::Esc => {
-                self.action = Action::Back;
+                Action::from(&self.parts, false)
             }
+            KeyEvent::Esc => Action::Back,
             KeyEvent::Backspace => {
-                if self.raw == "" {
-                    self.action = Action::Back;
+                if self.raw.is_empty() {
+                    Action::Back
                 } else {
                     self.raw.pop();
                     self.parts = CommandParts::from(&self.raw);
-                    self.action = Action::from(&self.parts, false);
+                    Action::from(&self.parts, false)
                 }
             }
-            _ => {}
+            _ => Action::Unparsed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap_with_chord() -> Keymap {
+        let mut km = Keymap::new();
+        km.insert(KeyEvent::Char('g'), Action::Refresh);
+        km.insert_chord(
+            &[KeyEvent::Char('g'), KeyEvent::Char('g')],
+            Action::MoveSelection(-1),
+        );
+        km
+    }
+
+    #[test]
+    fn chord_completes_on_second_key() {
+        let mut cmd = Command::with_keymap(keymap_with_chord());
+        cmd.add_key(KeyEvent::Char('g'));
+        assert!(matches!(cmd.action, Action::Unparsed));
+        assert_eq!(cmd.pending.len(), 1);
+        cmd.add_key(KeyEvent::Char('g'));
+        assert!(matches!(cmd.action, Action::MoveSelection(-1)));
+        assert!(cmd.pending.is_empty());
+    }
+
+    #[test]
+    fn ambiguous_prefix_fires_instead_of_being_dropped() {
+        let mut cmd = Command::with_keymap(keymap_with_chord());
+        cmd.add_key(KeyEvent::Char('g'));
+        cmd.add_key(KeyEvent::Char('x'));
+        match &cmd.action {
+            Action::Sequence(actions) => {
+                assert!(matches!(actions[0], Action::Refresh));
+            }
+            other => panic!("expected a Sequence starting with Refresh, got {:?}", other),
         }
+        // 'x' wasn't part of the abandoned chord, so it's still typed
+        assert_eq!(cmd.raw, "x");
+    }
+
+    #[test]
+    fn esc_aborts_a_pending_chord() {
+        let mut cmd = Command::with_keymap(keymap_with_chord());
+        cmd.add_key(KeyEvent::Char('g'));
+        cmd.add_key(KeyEvent::Esc);
+        assert!(cmd.pending.is_empty());
+        // Esc must plainly abort, not fire 'g's own binding (Refresh) chained
+        // with Back - it's not "just another key that doesn't extend the chord"
+        assert!(matches!(cmd.action, Action::Back));
+    }
+
+    #[test]
+    fn unbound_key_falls_back_to_char_insertion() {
+        let mut cmd = Command::new();
+        cmd.add_key(KeyEvent::Char('a'));
+        assert_eq!(cmd.raw, "a");
+    }
+
+    #[test]
+    fn question_mark_is_typed_when_not_in_help_context() {
+        let mut km = Keymap::new();
+        km.insert(KeyEvent::Char('?'), Action::Refresh);
+        let mut cmd = Command::with_keymap(km);
+        cmd.add_key(KeyEvent::Char('a'));
+        cmd.add_key(KeyEvent::Char('?'));
+        // mid-pattern, '?' is just a character: the keymap binding must not
+        // steal it, even though it's a complete binding on its own
+        assert_eq!(cmd.raw, "a?");
+        assert!(!matches!(cmd.action, Action::Refresh));
+    }
+
+    #[test]
+    fn question_mark_opens_remapped_help_at_start_of_input() {
+        let mut km = Keymap::new();
+        km.insert(KeyEvent::Char('?'), Action::Refresh);
+        let mut cmd = Command::with_keymap(km);
+        cmd.add_key(KeyEvent::Char('?'));
+        assert!(matches!(cmd.action, Action::Refresh));
+        assert_eq!(cmd.raw, "");
+    }
+
+    #[test]
+    fn split_unquoted_ignores_separator_inside_quotes() {
+        let parts = split_unquoted(r#"/foo:mv "a;b" dest;/bar:cp dest"#, SCRIPT_SEPARATOR);
+        assert_eq!(parts, vec![r#"/foo:mv "a;b" dest"#, "/bar:cp dest"]);
+    }
+
+    #[test]
+    fn parse_script_keeps_a_quoted_separator_in_one_command() {
+        let commands = Command::parse_script(r#"/foo:mv "a;b" dest;/bar:cp dest"#);
+        assert_eq!(commands.len(), 2);
+    }
+
+    #[test]
+    fn parse_script_drops_empty_segments() {
+        let commands = Command::parse_script(";;/foo:rm;;");
+        assert_eq!(commands.len(), 1);
+    }
+
+    #[test]
+    fn parse_script_with_separator_uses_the_given_separator() {
+        let commands = Command::parse_script_with_separator("/foo:rm|/bar:cp dest", '|');
+        assert_eq!(commands.len(), 2);
     }
 }
@@ -0,0 +1,136 @@
+//! parsing of the part of user input (or of a verb's `execution` pattern)
+//!  which names a verb and gives it arguments
+
+/// the parsed invocation of a verb: its name and the arguments given to it.
+/// `args` keeps the raw string (so verbs wanting it untouched, like `$args`
+///  substitution, still can), while `args_tokens` is the shell-style split
+///  of that string, quote and escape aware, which is what verbs wanting
+///  individual arguments (e.g. a path with spaces) should use.
+#[derive(Debug, Clone)]
+pub struct VerbInvocation {
+    pub name: String,
+    pub args: Option<String>,
+    pub args_tokens: Vec<String>,
+}
+
+impl VerbInvocation {
+    pub fn from(invocation: &str) -> VerbInvocation {
+        let mut parts = invocation.splitn(2, ' ');
+        let name = parts.next().unwrap_or("").to_string();
+        let args = parts.next().map(|s| s.to_string());
+        let args_tokens = match &args {
+            Some(args) => tokenize(args),
+            None => Vec::new(),
+        };
+        VerbInvocation {
+            name,
+            args,
+            args_tokens,
+        }
+    }
+}
+
+/// split a verb's argument string into tokens, the way a shell would:
+///  whitespace separates tokens unless quoted, `'...'` and `"..."` keep
+///  their content (separators included) as one token, and a backslash
+///  escapes the character following it (including a quote or itself),
+///  except inside single quotes, where it's kept literally.
+/// This lets e.g. `:mv "my file.txt" dest` pass `my file.txt` as a
+///  single argument instead of splitting on its inner space.
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut in_token = false;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for c in s.chars() {
+        if escaped {
+            token.push(c);
+            escaped = false;
+            continue;
+        }
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else {
+                    token.push(c);
+                }
+            }
+            None => {
+                if c == '\\' {
+                    escaped = true;
+                    in_token = true;
+                } else if c == '\'' || c == '"' {
+                    quote = Some(c);
+                    in_token = true;
+                } else if c.is_whitespace() {
+                    if in_token {
+                        tokens.push(std::mem::take(&mut token));
+                        in_token = false;
+                    }
+                } else {
+                    token.push(c);
+                    in_token = true;
+                }
+            }
+        }
+    }
+    if in_token || quote.is_some() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(tokenize("a b  c"), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn keeps_quoted_spaces_together() {
+        assert_eq!(tokenize(r#""my file.txt" dest"#), vec!["my file.txt", "dest"]);
+        assert_eq!(tokenize("'my file.txt' dest"), vec!["my file.txt", "dest"]);
+    }
+
+    #[test]
+    fn backslash_escapes_a_single_character() {
+        assert_eq!(tokenize("a\\ b c"), vec!["a b", "c"]);
+    }
+
+    #[test]
+    fn backslash_escapes_a_quote_inside_double_quotes() {
+        assert_eq!(tokenize(r#""a\"b""#), vec!["a\"b"]);
+    }
+
+    #[test]
+    fn trailing_backslash_is_dropped() {
+        assert_eq!(tokenize("a\\"), vec!["a"]);
+    }
+
+    #[test]
+    fn empty_input_has_no_tokens() {
+        assert_eq!(tokenize(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn verb_invocation_splits_name_and_tokenizes_args() {
+        let vi = VerbInvocation::from(r#"mv "my file.txt" dest"#);
+        assert_eq!(vi.name, "mv");
+        assert_eq!(vi.args_tokens, vec!["my file.txt", "dest"]);
+    }
+
+    #[test]
+    fn verb_invocation_without_args_has_no_tokens() {
+        let vi = VerbInvocation::from("edit");
+        assert_eq!(vi.name, "edit");
+        assert!(vi.args.is_none());
+        assert!(vi.args_tokens.is_empty());
+    }
+}